@@ -0,0 +1,182 @@
+//! A whole-corpus index built in a first pass over every input file, so
+//! the rendering pass can resolve cross-page links (including redirects
+//! and case differences) and report backlinks without re-reading other
+//! pages.
+
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// Canonicalize a page name (a file stem or a decoded href target) so
+/// that lookups are stable across casing differences.
+pub fn canonical_page_name(name: &str) -> String {
+    name.to_lowercase()
+}
+
+/// Pull the page-name portion out of an internal wiki href, e.g.
+/// `/wiki/Some_Page`, `Some_Page.html` and `Some_Page#History` all
+/// become `Some_Page`. Returns `None` for external links and anchors,
+/// which are not pages in this corpus.
+pub fn wiki_page_name_from_href(href: &str) -> Option<String> {
+    if href.is_empty()
+        || href.starts_with('#')
+        || href.starts_with("http://")
+        || href.starts_with("https://")
+    {
+        return None;
+    }
+
+    let page_name = href.split('#').next().unwrap_or(href);
+    let page_name = page_name
+        .strip_prefix("/wiki/")
+        .or_else(|| page_name.strip_prefix("wiki/"))
+        .unwrap_or(page_name);
+    let page_name = page_name.strip_suffix(".html").unwrap_or(page_name);
+    let page_name = urlencoding::decode(page_name)
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or_else(|_| page_name.to_string());
+
+    Some(page_name)
+}
+
+#[derive(Default)]
+pub struct PageIndex {
+    /// Canonical page name -> where its rendered page will live.
+    output_paths: HashMap<String, std::path::PathBuf>,
+    /// Canonical page name -> its displayed title.
+    titles: HashMap<String, String>,
+    /// Canonical page name -> canonical name it redirects to, for pages
+    /// that are themselves MediaWiki redirect stubs.
+    redirects: HashMap<String, String>,
+    /// Canonical page name -> canonical names of the pages it links to.
+    outgoing_links: HashMap<String, HashSet<String>>,
+}
+
+impl PageIndex {
+    fn merge(mut self, other: PageIndex) -> Self {
+        self.output_paths.extend(other.output_paths);
+        self.titles.extend(other.titles);
+        self.redirects.extend(other.redirects);
+        self.outgoing_links.extend(other.outgoing_links);
+        self
+    }
+
+    /// Build the whole-corpus index in parallel: one small `PageIndex`
+    /// per input file, reduced into a single one.
+    pub fn build(
+        input_files: &[std::path::PathBuf],
+        output_folder: &std::path::Path,
+        output_extension: &str,
+    ) -> PageIndex {
+        input_files
+            .par_iter()
+            .map(|input_file| Self::index_one_page(input_file, output_folder, output_extension))
+            .reduce(PageIndex::default, PageIndex::merge)
+    }
+
+    fn index_one_page(
+        input_file: &std::path::Path,
+        output_folder: &std::path::Path,
+        output_extension: &str,
+    ) -> PageIndex {
+        let file_name_str = match input_file.file_name().and_then(|n| n.to_str()) {
+            Some(name) if !name.starts_with('_') => name.to_string(),
+            _ => return PageIndex::default(),
+        };
+
+        let content = match std::fs::read_to_string(input_file) {
+            Ok(content) => content,
+            Err(_) => return PageIndex::default(),
+        };
+        let document = scraper::Html::parse_document(&content);
+
+        let stem = std::path::Path::new(&file_name_str)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&file_name_str)
+            .to_string();
+        let canonical_name = canonical_page_name(&stem);
+
+        let output_path = {
+            let mut output_path = output_folder.to_owned();
+            output_path.push(&stem);
+            output_path.set_extension(output_extension);
+            output_path
+        };
+
+        let mut index = PageIndex::default();
+        index
+            .output_paths
+            .insert(canonical_name.clone(), output_path);
+
+        if let Some(title) = document
+            .select(&scraper::Selector::parse("#firstHeading,#section_0").unwrap())
+            .next()
+        {
+            index
+                .titles
+                .insert(canonical_name.clone(), title.inner_html());
+        }
+
+        if let Some(redirect_target) = document
+            .select(&scraper::Selector::parse(".redirectMsg a").unwrap())
+            .next()
+            .and_then(|a| a.value().attr("href"))
+            .and_then(wiki_page_name_from_href)
+        {
+            index.redirects.insert(
+                canonical_name.clone(),
+                canonical_page_name(&redirect_target),
+            );
+        }
+
+        let outgoing_links = document
+            .select(&scraper::Selector::parse("a[href]").unwrap())
+            .filter_map(|a| a.value().attr("href"))
+            .filter_map(wiki_page_name_from_href)
+            .map(|name| canonical_page_name(&name))
+            .collect::<HashSet<_>>();
+        index.outgoing_links.insert(canonical_name, outgoing_links);
+
+        index
+    }
+
+    /// Follow redirect stubs to the page a link should ultimately land
+    /// on. Bounded so a redirect cycle can't loop forever.
+    fn resolve(&self, name: &str) -> String {
+        let mut current = name.to_string();
+        for _ in 0..8 {
+            match self.redirects.get(&current) {
+                Some(target) if target != &current => current = target.clone(),
+                _ => break,
+            }
+        }
+        current
+    }
+
+    pub fn output_path_for(&self, page_name: &str) -> Option<&std::path::PathBuf> {
+        self.output_paths
+            .get(&self.resolve(&canonical_page_name(page_name)))
+    }
+
+    pub fn title_for(&self, canonical_name: &str) -> Option<&String> {
+        self.titles.get(canonical_name)
+    }
+
+    /// Canonical names of the pages that link to the page `canonical_name`
+    /// resolves to, directly or through a redirect stub, sorted for
+    /// deterministic output.
+    pub fn backlinks_for(&self, canonical_name: &str) -> Vec<&str> {
+        let target = self.resolve(canonical_name);
+        let mut backlinks = self
+            .outgoing_links
+            .iter()
+            .filter(|(source, targets)| {
+                source.as_str() != canonical_name
+                    && targets.iter().any(|linked| self.resolve(linked) == target)
+            })
+            .map(|(source, _)| source.as_str())
+            .collect::<Vec<_>>();
+        backlinks.sort_unstable();
+        backlinks
+    }
+}
@@ -1,54 +1,36 @@
 use rayon::prelude::*;
 use std::io::Read;
 
-use markdown_gen::markdown::AsMarkdown;
+mod index;
+mod renderer;
 
-#[derive(Copy, Clone, Debug)]
-pub struct QSSpec;
+use index::{canonical_page_name, wiki_page_name_from_href, PageIndex};
+use renderer::{GemtextRenderer, MarkdownRenderer, Renderer, Span};
 
-impl quoted_string::spec::GeneralQSSpec for QSSpec {
-    type Quoting = Self;
-    type Parsing = QSParse;
+#[derive(Copy, Clone, Debug)]
+enum Backend {
+    Markdown,
+    Gemtext,
 }
 
-impl quoted_string::spec::QuotingClassifier for QSSpec {
-    fn classify_for_quoting(
-        pcp: quoted_string::spec::PartialCodePoint,
-    ) -> quoted_string::spec::QuotingClass {
-        match pcp.as_u8() {
-            b'"' | b'\\' => quoted_string::spec::QuotingClass::NeedsQuoting,
-            _ => quoted_string::spec::QuotingClass::QText,
+impl Backend {
+    fn extension(self) -> &'static str {
+        match self {
+            Backend::Markdown => "md",
+            Backend::Gemtext => "gmi",
         }
     }
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
-pub struct QSParse;
-
-impl quoted_string::spec::ParsingImpl for QSParse {
-    fn can_be_quoted(_: quoted_string::spec::PartialCodePoint) -> bool {
-        true
-    }
-
-    fn handle_normal_state(
-        _: quoted_string::spec::PartialCodePoint,
-    ) -> Result<(quoted_string::spec::State<Self>, bool), quoted_string::error::CoreError> {
-        Ok((quoted_string::spec::State::Normal, true))
-    }
-
-    fn advance(
-        &self,
-        _: quoted_string::spec::PartialCodePoint,
-    ) -> Result<(quoted_string::spec::State<Self>, bool), quoted_string::error::CoreError> {
-        Ok((quoted_string::spec::State::Normal, false))
-    }
-}
-
 #[derive(Debug)]
 pub enum ContentType {
     Header(String, usize),
     Paragraph(String, bool),
     List(Vec<String>),
+    Table {
+        headers: Vec<Span>,
+        rows: Vec<Vec<Span>>,
+    },
 }
 
 fn main() {
@@ -56,12 +38,24 @@ fn main() {
 
     let input_folder = args.get(1).unwrap();
     let output_folder = args.get(2).unwrap();
+    let toc_enabled = args.iter().any(|arg| arg == "--toc");
+    let backend = if args.iter().any(|arg| arg == "--gemtext") {
+        Backend::Gemtext
+    } else {
+        Backend::Markdown
+    };
 
     let input_files = collect_files(std::path::Path::new(input_folder));
+    let output_folder = std::path::Path::new(output_folder);
+
+    // First pass: learn how every page maps to its output path and what
+    // it links to, so the second pass can rewrite cross-page links and
+    // report backlinks without re-reading other pages.
+    let index = PageIndex::build(&input_files, output_folder, backend.extension());
 
-    input_files
-        .par_iter()
-        .for_each(|input_path| generate_markdown(input_path, std::path::Path::new(output_folder)));
+    input_files.par_iter().for_each(|input_path| {
+        generate_markdown(input_path, output_folder, toc_enabled, backend, &index)
+    });
 }
 
 fn collect_files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
@@ -82,7 +76,197 @@ fn collect_files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
     result
 }
 
-fn generate_markdown(input_file: &std::path::Path, output_folder: &std::path::Path) -> () {
+/// Walk the children of an inline HTML node, building a single `Span`
+/// out of each one. `output_file` and `index` are needed to resolve
+/// internal wiki links relative to the page being written.
+fn build_span_children(
+    node: scraper::element_ref::ElementRef,
+    output_file: &std::path::Path,
+    index: &PageIndex,
+) -> Span {
+    let mut parts = Vec::new();
+    for child in node.children() {
+        if let Some(text) = child.value().as_text() {
+            parts.push(Span::Text(text.to_string()));
+        } else if let Some(element) = scraper::element_ref::ElementRef::wrap(child) {
+            parts.push(build_span_element(element, output_file, index));
+        }
+    }
+    Span::Concat(parts)
+}
+
+/// Convert a single inline HTML element (and its descendants) to the
+/// matching `Span`. Unknown elements are transparent: their children
+/// are still converted so nested markup is not lost.
+fn build_span_element(
+    element: scraper::element_ref::ElementRef,
+    output_file: &std::path::Path,
+    index: &PageIndex,
+) -> Span {
+    match element.value().name() {
+        "a" => {
+            let href = element.value().attr("href").unwrap_or_default();
+            let href = rewrite_wiki_link(href, output_file, index);
+            Span::Link(
+                href,
+                Box::new(build_span_children(element, output_file, index)),
+            )
+        }
+        "b" | "strong" => Span::Bold(Box::new(build_span_children(element, output_file, index))),
+        "i" | "em" => Span::Italic(Box::new(build_span_children(element, output_file, index))),
+        "code" => Span::Code(Box::new(build_span_children(element, output_file, index))),
+        "br" => Span::LineBreak,
+        _ => build_span_children(element, output_file, index),
+    }
+}
+
+/// Rewrite an internal wiki href (e.g. `/wiki/Some_Page` or
+/// `Some_Page.html`) into a relative path pointing at the generated
+/// sibling page for that page, using the whole-corpus index so
+/// redirects and casing differences still resolve. External links and
+/// anchors are left untouched.
+fn rewrite_wiki_link(href: &str, output_file: &std::path::Path, index: &PageIndex) -> String {
+    let fragment = href.split_once('#').map(|(_, fragment)| fragment);
+
+    let page_name = match wiki_page_name_from_href(href) {
+        Some(page_name) => page_name,
+        None => return href.to_string(),
+    };
+
+    let target_file = match index.output_path_for(&page_name) {
+        Some(target_file) => target_file.clone(),
+        None => {
+            // Not a page the index knows about; fall back to assuming it
+            // sits alongside this one with the same extension.
+            let extension = output_file
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("md");
+            let mut fallback = output_file
+                .parent()
+                .unwrap_or_else(|| std::path::Path::new(""))
+                .to_owned();
+            fallback.push(format!("{}.{}", page_name, extension));
+            fallback
+        }
+    };
+
+    let output_dir = output_file
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new(""));
+
+    let rewritten = match pathdiff::diff_paths(&target_file, output_dir) {
+        // A link to the page currently being rendered becomes a same-page
+        // anchor instead of a link back to its own file.
+        Some(_) if target_file == output_file => "".to_string(),
+        Some(relative_path) => {
+            urlencoding::encode(relative_path.to_str().unwrap_or_default()).to_string()
+        }
+        None => return href.to_string(),
+    };
+
+    match fragment {
+        Some(fragment) => format!("{}#{}", rewritten, fragment),
+        None if rewritten.is_empty() => "#".to_string(),
+        None => rewritten,
+    }
+}
+
+/// Convert a `<table>` element into headers + rows, using the first row
+/// (however it is marked up) as the header and padding ragged rows out
+/// to the header width.
+fn convert_table(
+    table: scraper::element_ref::ElementRef,
+    output_file: &std::path::Path,
+    index: &PageIndex,
+) -> ContentType {
+    let row_selector = scraper::Selector::parse("tr").unwrap();
+    let cell_selector = scraper::Selector::parse("th, td").unwrap();
+
+    let mut rows = table.select(&row_selector);
+
+    let headers = rows
+        .next()
+        .map(|tr| {
+            tr.select(&cell_selector)
+                .map(|cell| build_span_children(cell, output_file, index))
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let rows = rows
+        .map(|tr| {
+            let mut cells = tr
+                .select(&cell_selector)
+                .map(|cell| build_span_children(cell, output_file, index))
+                .collect::<Vec<_>>();
+            cells.resize_with(headers.len(), || Span::Text(String::new()));
+            cells
+        })
+        .collect();
+
+    ContentType::Table { headers, rows }
+}
+
+/// Strip HTML tags from a fragment, keeping only the concatenated text
+/// of its nodes. Used to turn heading markup (which may itself contain
+/// links or emphasis) into plain text for slugging.
+fn strip_html_tags(html: &str) -> String {
+    scraper::Html::parse_fragment(html)
+        .root_element()
+        .text()
+        .collect::<String>()
+}
+
+/// Produce a GitHub-style anchor slug for a heading's text, disambiguating
+/// repeats seen earlier on the same page by appending `-1`, `-2`, etc.
+fn slugify_heading(text: &str, seen: &mut std::collections::HashMap<String, usize>) -> String {
+    let plain = strip_html_tags(text).to_lowercase();
+
+    let mut slug = String::with_capacity(plain.len());
+    let mut prev_was_hyphen = true;
+    for ch in plain.chars() {
+        if ch.is_alphanumeric() {
+            slug.push(ch);
+            prev_was_hyphen = false;
+        } else if !prev_was_hyphen {
+            slug.push('-');
+            prev_was_hyphen = true;
+        }
+    }
+    let slug = slug.trim_matches('-').to_string();
+
+    let count = seen.entry(slug.clone()).or_insert(0);
+    let disambiguated = if *count == 0 {
+        slug
+    } else {
+        format!("{}-{}", slug, count)
+    };
+    *count += 1;
+    disambiguated
+}
+
+/// Resolve each heading to its `(level, display text, anchor slug)`, for
+/// a `Renderer` to lay out in whatever table-of-contents syntax its
+/// output format supports.
+fn generate_table_of_contents(headings: &[(usize, String)]) -> Vec<(usize, String, String)> {
+    let mut seen = std::collections::HashMap::new();
+    headings
+        .iter()
+        .map(|(level, text)| {
+            let slug = slugify_heading(text, &mut seen);
+            (*level, strip_html_tags(text), slug)
+        })
+        .collect()
+}
+
+fn generate_markdown(
+    input_file: &std::path::Path,
+    output_folder: &std::path::Path,
+    toc_enabled: bool,
+    backend: Backend,
+    index: &PageIndex,
+) -> () {
     let file_name_str = input_file
         .file_name()
         .unwrap()
@@ -108,12 +292,15 @@ fn generate_markdown(input_file: &std::path::Path, output_folder: &std::path::Pa
     let output_file = {
         let mut output_file = output_folder.to_owned();
         output_file.push(file_name.as_path());
-        output_file.set_extension("md");
+        output_file.set_extension(backend.extension());
         output_file
     };
 
     let file = std::fs::File::create(&output_file).unwrap();
-    let mut md = markdown_gen::markdown::Markdown::new(file);
+    let mut renderer: Box<dyn Renderer> = match backend {
+        Backend::Markdown => Box::new(MarkdownRenderer::new(file)),
+        Backend::Gemtext => Box::new(GemtextRenderer::new(file)),
+    };
 
     let document = scraper::Html::parse_document(&content);
 
@@ -128,16 +315,7 @@ fn generate_markdown(input_file: &std::path::Path, output_folder: &std::path::Pa
         "UNKNOWN".to_string()
     };
 
-    md.write_raw(markdown_gen::markdown::RichText::new(
-        format!(
-            "+++\ntitle= {}\n+++",
-            quoted_string::quote::<QSSpec>(&title)
-                .map_err(|_| title)
-                .unwrap()
-        )
-        .as_str(),
-    ))
-    .unwrap();
+    renderer.frontmatter(&title);
 
     // CONTENT
 
@@ -173,27 +351,58 @@ fn generate_markdown(input_file: &std::path::Path, output_folder: &std::path::Pa
                         .map(|v| v.inner_html())
                         .collect::<Vec<_>>(),
                 )),
+                "table" => Some(convert_table(v, &output_file, index)),
                 _ => None,
             })
             .collect::<Vec<_>>();
 
+        if toc_enabled {
+            let headings = contents
+                .iter()
+                .filter_map(|c| match c {
+                    ContentType::Header(text, level) => Some((*level, text.clone())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+
+            if headings.len() >= 2 {
+                renderer.toc(&generate_table_of_contents(&headings));
+            }
+        }
+
         for content in contents {
             match content {
-                ContentType::Header(text, level) => md.write(text.as_str().heading(level)).unwrap(),
+                ContentType::Header(text, level) => renderer.heading(level, &Span::Text(text)),
                 ContentType::Paragraph(text, is_text_only) => {
                     if is_text_only {
-                        md.write(text.as_str().paragraph()).unwrap()
+                        renderer.paragraph(&Span::Text(text));
                     } else {
-                        // Otherwise need to parse again
+                        // Re-parse the paragraph's inner HTML on its own so we can
+                        // walk its node tree and recover links, bold, italics, etc.
+                        let fragment = scraper::Html::parse_fragment(&format!("<p>{}</p>", text));
+                        if let Some(p) = fragment
+                            .select(&scraper::Selector::parse("p").unwrap())
+                            .next()
+                        {
+                            renderer.paragraph(&build_span_children(p, &output_file, index));
+                        }
                     }
                 }
                 ContentType::List(list) => {
-                    let mut md_list = markdown_gen::markdown::ListOwned::new(false);
-                    for x in list {
-                        md_list.push(x);
-                    }
-                    md.write_raw(md_list).unwrap();
+                    let items = list
+                        .into_iter()
+                        .filter_map(|item| {
+                            let fragment =
+                                scraper::Html::parse_fragment(&format!("<li>{}</li>", item));
+                            fragment
+                                .select(&scraper::Selector::parse("li").unwrap())
+                                .next()
+                                .map(|li| build_span_children(li, &output_file, index))
+                        })
+                        .collect::<Vec<_>>();
+                    renderer.list(&items);
                 }
+                ContentType::Table { headers, rows } => renderer.table(&headers, &rows),
             }
         }
     } else {
@@ -203,17 +412,51 @@ fn generate_markdown(input_file: &std::path::Path, output_folder: &std::path::Pa
     // FOOTER
 
     let relative_path = urlencoding::encode(
-        pathdiff::diff_paths(input_file, output_file)
+        pathdiff::diff_paths(input_file, &output_file)
             .unwrap()
             .to_str()
             .unwrap(),
     )
     .to_string();
 
-    md.write(
-        "generated from "
-            .paragraph()
-            .append(file_name.to_str().unwrap().bold().link_to(&relative_path)),
-    )
-    .unwrap();
+    renderer.paragraph(&Span::Concat(vec![
+        Span::Text("generated from ".to_string()),
+        Span::Link(
+            relative_path,
+            Box::new(Span::Bold(Box::new(Span::Text(
+                file_name.to_str().unwrap().to_string(),
+            )))),
+        ),
+    ]));
+
+    // REFERENCED BY
+
+    let canonical_name = canonical_page_name(
+        file_name
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or(&file_name_str),
+    );
+    let backlinks = index.backlinks_for(&canonical_name);
+    if !backlinks.is_empty() {
+        renderer.heading(1, &Span::Text("Referenced by".to_string()));
+        let items = backlinks
+            .into_iter()
+            .filter_map(|source| {
+                let target_file = index.output_path_for(source)?;
+                let output_dir = output_file
+                    .parent()
+                    .unwrap_or_else(|| std::path::Path::new(""));
+                let relative_path =
+                    urlencoding::encode(pathdiff::diff_paths(target_file, output_dir)?.to_str()?)
+                        .to_string();
+                let title = index
+                    .title_for(source)
+                    .cloned()
+                    .unwrap_or_else(|| source.to_string());
+                Some(Span::Link(relative_path, Box::new(Span::Text(title))))
+            })
+            .collect::<Vec<_>>();
+        renderer.list(&items);
+    }
 }
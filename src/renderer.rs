@@ -0,0 +1,342 @@
+//! Output backends for the converted wiki pages.
+//!
+//! Page content is built up as [`Span`] trees (format-agnostic inline
+//! markup) and handed to a [`Renderer`], which is free to lay it out
+//! however its target format requires.
+
+use std::io::Write;
+
+#[derive(Copy, Clone, Debug)]
+pub struct QSSpec;
+
+impl quoted_string::spec::GeneralQSSpec for QSSpec {
+    type Quoting = Self;
+    type Parsing = QSParse;
+}
+
+impl quoted_string::spec::QuotingClassifier for QSSpec {
+    fn classify_for_quoting(
+        pcp: quoted_string::spec::PartialCodePoint,
+    ) -> quoted_string::spec::QuotingClass {
+        match pcp.as_u8() {
+            b'"' | b'\\' => quoted_string::spec::QuotingClass::NeedsQuoting,
+            _ => quoted_string::spec::QuotingClass::QText,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct QSParse;
+
+impl quoted_string::spec::ParsingImpl for QSParse {
+    fn can_be_quoted(_: quoted_string::spec::PartialCodePoint) -> bool {
+        true
+    }
+
+    fn handle_normal_state(
+        _: quoted_string::spec::PartialCodePoint,
+    ) -> Result<(quoted_string::spec::State<Self>, bool), quoted_string::error::CoreError> {
+        Ok((quoted_string::spec::State::Normal, true))
+    }
+
+    fn advance(
+        &self,
+        _: quoted_string::spec::PartialCodePoint,
+    ) -> Result<(quoted_string::spec::State<Self>, bool), quoted_string::error::CoreError> {
+        Ok((quoted_string::spec::State::Normal, false))
+    }
+}
+
+/// Format-agnostic inline markup, built while walking a page's HTML so
+/// that any [`Renderer`] can decide how to lay it out.
+#[derive(Debug, Clone)]
+pub enum Span {
+    Text(String),
+    Bold(Box<Span>),
+    Italic(Box<Span>),
+    Code(Box<Span>),
+    Link(String, Box<Span>),
+    LineBreak,
+    Concat(Vec<Span>),
+}
+
+/// A page output backend. One page is rendered as a `frontmatter` call,
+/// followed by a sequence of block-level calls in document order.
+pub trait Renderer {
+    fn frontmatter(&mut self, title: &str);
+    fn heading(&mut self, level: usize, text: &Span);
+    fn paragraph(&mut self, text: &Span);
+    fn list(&mut self, items: &[Span]);
+    fn table(&mut self, headers: &[Span], rows: &[Vec<Span>]);
+    /// A table of contents: `(level, display text, anchor slug)` per
+    /// heading, in document order.
+    fn toc(&mut self, entries: &[(usize, String, String)]);
+    /// A pre-rendered block, passed through to the writer mostly as-is.
+    fn raw(&mut self, text: &str);
+}
+
+fn span_to_richtext(span: &Span) -> markdown_gen::markdown::RichText {
+    match span {
+        Span::Text(text) => markdown_gen::markdown::RichText::new(text.as_str()),
+        Span::Bold(inner) => span_to_richtext(inner).bold(),
+        Span::Italic(inner) => span_to_richtext(inner).italic(),
+        Span::Code(inner) => span_to_richtext(inner).code(),
+        Span::Link(url, inner) => span_to_richtext(inner).link_to(url),
+        Span::LineBreak => markdown_gen::markdown::RichText::new("\n"),
+        Span::Concat(parts) => {
+            let mut result = markdown_gen::markdown::RichText::new("");
+            for part in parts {
+                result = result.append(span_to_richtext(part));
+            }
+            result
+        }
+    }
+}
+
+/// Render a span to markdown text (`**bold**`, `[text](url)`, etc.) by
+/// writing it through a throwaway `Markdown` writer, the same way
+/// `MarkdownRenderer::paragraph` renders it. Used so table cells keep
+/// their links and emphasis instead of being flattened to plain text.
+fn render_cell_markdown(span: &Span) -> String {
+    let mut buffer = Vec::new();
+    {
+        use markdown_gen::markdown::AsMarkdown;
+        let mut md = markdown_gen::markdown::Markdown::new(&mut buffer);
+        md.write("".paragraph().append(span_to_richtext(span)))
+            .unwrap();
+    }
+    String::from_utf8(buffer).unwrap().trim().to_string()
+}
+
+fn span_to_plain_text(span: &Span) -> String {
+    match span {
+        Span::Text(text) => text.clone(),
+        Span::Bold(inner) | Span::Italic(inner) | Span::Code(inner) => span_to_plain_text(inner),
+        Span::Link(_, inner) => span_to_plain_text(inner),
+        Span::LineBreak => "\n".to_string(),
+        Span::Concat(parts) => parts.iter().map(span_to_plain_text).collect(),
+    }
+}
+
+/// Renders pages as the markdown this crate has always produced.
+pub struct MarkdownRenderer<W: Write> {
+    md: markdown_gen::markdown::Markdown<W>,
+}
+
+impl<W: Write> MarkdownRenderer<W> {
+    pub fn new(writer: W) -> Self {
+        MarkdownRenderer {
+            md: markdown_gen::markdown::Markdown::new(writer),
+        }
+    }
+}
+
+impl<W: Write> Renderer for MarkdownRenderer<W> {
+    fn frontmatter(&mut self, title: &str) {
+        self.md
+            .write_raw(markdown_gen::markdown::RichText::new(
+                format!(
+                    "+++\ntitle= {}\n+++",
+                    quoted_string::quote::<QSSpec>(title)
+                        .map_err(|_| title.to_string())
+                        .unwrap()
+                )
+                .as_str(),
+            ))
+            .unwrap();
+    }
+
+    fn heading(&mut self, level: usize, text: &Span) {
+        use markdown_gen::markdown::AsMarkdown;
+        self.md
+            .write(span_to_plain_text(text).as_str().heading(level))
+            .unwrap();
+    }
+
+    fn paragraph(&mut self, text: &Span) {
+        use markdown_gen::markdown::AsMarkdown;
+        self.md
+            .write("".paragraph().append(span_to_richtext(text)))
+            .unwrap();
+    }
+
+    fn list(&mut self, items: &[Span]) {
+        let mut md_list = markdown_gen::markdown::ListOwned::new(false);
+        for item in items {
+            md_list.push(span_to_richtext(item));
+        }
+        self.md.write_raw(md_list).unwrap();
+    }
+
+    fn table(&mut self, headers: &[Span], rows: &[Vec<Span>]) {
+        let column_count = headers.len();
+        if column_count == 0 {
+            return;
+        }
+
+        let cell_text = |span: &Span| render_cell_markdown(span).replace('|', "\\|");
+
+        let mut table_text = format!(
+            "| {} |\n",
+            headers
+                .iter()
+                .map(cell_text)
+                .collect::<Vec<_>>()
+                .join(" | ")
+        );
+        table_text.push('|');
+        table_text.push_str(&vec![" --- "; column_count].join("|"));
+        table_text.push_str("|\n");
+        for row in rows {
+            let mut cells = row.iter().map(cell_text).collect::<Vec<_>>();
+            cells.resize(column_count, String::new());
+            table_text.push_str(&format!("| {} |\n", cells.join(" | ")));
+        }
+
+        self.md
+            .write_raw(markdown_gen::markdown::RichText::new(table_text.trim_end()))
+            .unwrap();
+    }
+
+    fn toc(&mut self, entries: &[(usize, String, String)]) {
+        let mut toc_text = String::new();
+        for (level, text, slug) in entries {
+            let indent = "  ".repeat(level.saturating_sub(1));
+            toc_text.push_str(&format!("{}- [{}](#{})\n", indent, text, slug));
+        }
+        self.md
+            .write_raw(markdown_gen::markdown::RichText::new(toc_text.trim_end()))
+            .unwrap();
+    }
+
+    fn raw(&mut self, text: &str) {
+        self.md
+            .write_raw(markdown_gen::markdown::RichText::new(text))
+            .unwrap();
+    }
+}
+
+/// Flattens a span to plain text, collecting any links found along the
+/// way so the caller can flush them afterward as `=> url text` lines --
+/// gemtext has no inline link syntax.
+fn span_to_gemtext_text(span: &Span, links: &mut Vec<(String, String)>) -> String {
+    match span {
+        Span::Text(text) => text.clone(),
+        Span::Bold(inner) | Span::Italic(inner) | Span::Code(inner) => {
+            span_to_gemtext_text(inner, links)
+        }
+        Span::Link(url, inner) => {
+            let text = span_to_gemtext_text(inner, links);
+            links.push((url.clone(), text.clone()));
+            text
+        }
+        Span::LineBreak => "\n".to_string(),
+        Span::Concat(parts) => parts
+            .iter()
+            .map(|part| span_to_gemtext_text(part, links))
+            .collect(),
+    }
+}
+
+/// Renders pages as `text/gemini` (gemtext) for serving over Gemini.
+pub struct GemtextRenderer<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> GemtextRenderer<W> {
+    pub fn new(writer: W) -> Self {
+        GemtextRenderer { writer }
+    }
+
+    fn flush_links(&mut self, links: &mut Vec<(String, String)>) {
+        if links.is_empty() {
+            return;
+        }
+        for (url, text) in links.drain(..) {
+            writeln!(self.writer, "=> {} {}", url, text).unwrap();
+        }
+        writeln!(self.writer).unwrap();
+    }
+}
+
+impl<W: Write> Renderer for GemtextRenderer<W> {
+    fn frontmatter(&mut self, title: &str) {
+        writeln!(self.writer, "# {}\n", title).unwrap();
+    }
+
+    fn heading(&mut self, level: usize, text: &Span) {
+        let prefix = match level {
+            1 => "#",
+            2 => "##",
+            _ => "###",
+        };
+        let mut links = Vec::new();
+        let text = span_to_gemtext_text(text, &mut links);
+        writeln!(self.writer, "{} {}\n", prefix, text).unwrap();
+        self.flush_links(&mut links);
+    }
+
+    fn paragraph(&mut self, text: &Span) {
+        let mut links = Vec::new();
+        let text = span_to_gemtext_text(text, &mut links);
+        writeln!(self.writer, "{}\n", text).unwrap();
+        self.flush_links(&mut links);
+    }
+
+    fn list(&mut self, items: &[Span]) {
+        let mut links = Vec::new();
+        for item in items {
+            let text = span_to_gemtext_text(item, &mut links);
+            writeln!(self.writer, "* {}", text).unwrap();
+        }
+        writeln!(self.writer).unwrap();
+        self.flush_links(&mut links);
+    }
+
+    fn table(&mut self, headers: &[Span], rows: &[Vec<Span>]) {
+        let mut links = Vec::new();
+        writeln!(self.writer, "```").unwrap();
+        writeln!(
+            self.writer,
+            "{}",
+            headers
+                .iter()
+                .map(|h| span_to_gemtext_text(h, &mut links))
+                .collect::<Vec<_>>()
+                .join(" | ")
+        )
+        .unwrap();
+        for row in rows {
+            writeln!(
+                self.writer,
+                "{}",
+                row.iter()
+                    .map(|cell| span_to_gemtext_text(cell, &mut links))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            )
+            .unwrap();
+        }
+        writeln!(self.writer, "```\n").unwrap();
+        self.flush_links(&mut links);
+    }
+
+    fn toc(&mut self, entries: &[(usize, String, String)]) {
+        // Gemtext has no inline links and no concept of a heading anchor,
+        // so the contents are laid out as a flat bulleted outline with
+        // the (best-effort) anchors flushed afterward as `=> ` lines,
+        // same as any other link-bearing block in this renderer.
+        for (_level, text, _slug) in entries {
+            writeln!(self.writer, "* {}", text).unwrap();
+        }
+        writeln!(self.writer).unwrap();
+        for (_level, text, slug) in entries {
+            writeln!(self.writer, "=> #{} {}", slug, text).unwrap();
+        }
+        writeln!(self.writer).unwrap();
+    }
+
+    fn raw(&mut self, text: &str) {
+        writeln!(self.writer, "{}\n", text).unwrap();
+    }
+}